@@ -1,9 +1,11 @@
 use std::io;
 use tantivy;
+use serde_json;
 
 error_chain! {
     foreign_links {
         TantivyError(tantivy::Error);
         Io(io::Error);
+        Json(serde_json::Error);
     }
 }
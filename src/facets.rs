@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+
+use tantivy::{DocId, Result as TantivyResult, Score, SegmentLocalId, SegmentReader};
+use tantivy::collector::Collector;
+use tantivy::schema::Field;
+
+/// A search hit's matching nick, alongside how many hits it accounts for.
+#[derive(Debug, Serialize)]
+pub struct NickCount {
+    pub nick: String,
+    pub count: usize,
+}
+
+/// How many hits landed on a given day (`YYYY-MM-DD`).
+#[derive(Debug, Serialize)]
+pub struct DayCount {
+    pub day: String,
+    pub count: usize,
+}
+
+/// Accumulates, across a single `searcher.search` pass, how many matching
+/// documents belong to each nick and each day — turning a plain hit list
+/// into a lightweight "who talked about this, and when" view.
+pub struct FacetCollector {
+    nick_field: Field,
+    date_field: Field,
+    segment_reader: Option<SegmentReader>,
+    nick_counts: HashMap<String, usize>,
+    day_counts: HashMap<String, usize>,
+}
+
+impl FacetCollector {
+    pub fn new(nick_field: Field, date_field: Field) -> FacetCollector {
+        FacetCollector {
+            nick_field: nick_field,
+            date_field: date_field,
+            segment_reader: None,
+            nick_counts: HashMap::new(),
+            day_counts: HashMap::new(),
+        }
+    }
+
+    /// The `n` most-common nicks, highest count first (ties broken by nick).
+    pub fn top_nicks(&self, n: usize) -> Vec<NickCount> {
+        top_n(&self.nick_counts, n)
+            .into_iter()
+            .map(|(nick, count)| NickCount { nick, count })
+            .collect()
+    }
+
+    /// Per-day match counts, sorted chronologically.
+    pub fn activity_by_day(&self) -> Vec<DayCount> {
+        let mut days: Vec<(String, usize)> = self.day_counts.iter().map(|(d, &c)| (d.clone(), c)).collect();
+        days.sort_by(|a, b| a.0.cmp(&b.0));
+        days.into_iter().map(|(day, count)| DayCount { day, count }).collect()
+    }
+}
+
+impl Collector for FacetCollector {
+    fn set_segment(&mut self, _segment_local_id: SegmentLocalId, segment: &SegmentReader) -> TantivyResult<()> {
+        self.segment_reader = Some(segment.clone());
+        Ok(())
+    }
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        let segment_reader = match self.segment_reader.as_ref() {
+            Some(segment_reader) => segment_reader,
+            None => return,
+        };
+        let stored = match segment_reader.doc(doc) {
+            Ok(stored) => stored,
+            Err(_) => return,
+        };
+
+        if let Some(nick) = stored.get_first(self.nick_field).and_then(|v| v.text()) {
+            *self.nick_counts.entry(nick.to_owned()).or_insert(0) += 1;
+        }
+
+        if let Some(timestamp) = stored.get_first(self.date_field).and_then(|v| v.i64_value()) {
+            if let Some(day) = day_bucket(timestamp) {
+                *self.day_counts.entry(day).or_insert(0) += 1;
+            }
+        }
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+}
+
+fn day_bucket(timestamp: i64) -> Option<String> {
+    NaiveDateTime::from_timestamp_opt(timestamp, 0).map(|dt| dt.format("%Y-%m-%d").to_string())
+}
+
+fn top_n(counts: &HashMap<String, usize>, n: usize) -> Vec<(String, usize)> {
+    let mut entries: Vec<(String, usize)> = counts.iter().map(|(k, &v)| (k.clone(), v)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries.truncate(n);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_n_sorts_by_count_descending() {
+        let mut counts = HashMap::new();
+        counts.insert("alice".to_owned(), 3);
+        counts.insert("bob".to_owned(), 5);
+        counts.insert("carol".to_owned(), 1);
+
+        let top = top_n(&counts, 10);
+        assert_eq!(
+            top,
+            vec![
+                ("bob".to_owned(), 5),
+                ("alice".to_owned(), 3),
+                ("carol".to_owned(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn top_n_breaks_ties_alphabetically() {
+        let mut counts = HashMap::new();
+        counts.insert("bob".to_owned(), 2);
+        counts.insert("alice".to_owned(), 2);
+
+        let top = top_n(&counts, 10);
+        assert_eq!(top, vec![("alice".to_owned(), 2), ("bob".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn top_n_truncates_to_limit() {
+        let mut counts = HashMap::new();
+        counts.insert("alice".to_owned(), 1);
+        counts.insert("bob".to_owned(), 2);
+        counts.insert("carol".to_owned(), 3);
+
+        assert_eq!(top_n(&counts, 2).len(), 2);
+    }
+
+    #[test]
+    fn day_bucket_formats_timestamp_as_ymd() {
+        // 2021-03-01T12:00:00Z
+        assert_eq!(day_bucket(1614600000), Some("2021-03-01".to_owned()));
+    }
+}
@@ -0,0 +1,113 @@
+use regex::Regex;
+
+use errors::*;
+
+/// Named, built-in log layouts this tool can parse out of the box.
+///
+/// Every format's regex must expose `time`, `nick` and `msg` named capture
+/// groups. `time` may be either `HH:MM` or `HH:MM:SS` — whichever width a
+/// format captures is combined with the date taken from the log's filename.
+pub struct LogFormat {
+    pub name: String,
+    pub regex: Regex,
+}
+
+impl LogFormat {
+    /// Looks up one of the bundled formats by name, as passed to `--format`.
+    pub fn builtin(name: &str) -> Option<LogFormat> {
+        let regex = match name {
+            // The classic irssi/weechat-relay layout: "12:34 <nick> message"
+            "irssi" => Regex::new(r"(?x)
+                ^(?P<time>\d{2}:\d{2})\s
+                [+@&]?
+                \s*
+                (?P<nick>[^\s][^>]+)
+                >
+                \s
+                (?P<msg>.+)$").unwrap(),
+            // WeeChat's default `buffer_diff`/logger layout, tab-separated:
+            // "12:34:56\t@nick\tmessage"
+            "weechat" => Regex::new(r"(?x)
+                ^(?P<time>\d{2}:\d{2}:\d{2})
+                \t
+                [+@&~%]?
+                (?P<nick>\S+)
+                \t
+                (?P<msg>.+)$").unwrap(),
+            // ZNC's playback buffer layout: "[12:34:56] <nick> message"
+            "znc" => Regex::new(r"(?x)
+                ^\[(?P<time>\d{2}:\d{2}:\d{2})\]\s
+                <
+                [+@&~%]?
+                (?P<nick>[^>]+)
+                >
+                \s
+                (?P<msg>.+)$").unwrap(),
+            _ => return None,
+        };
+
+        Some(LogFormat { name: name.to_owned(), regex })
+    }
+
+    /// Compiles a user-supplied `--format-regex` pattern, making sure it
+    /// defines the three named capture groups every format relies on.
+    pub fn custom(pattern: &str) -> Result<LogFormat> {
+        let regex = Regex::new(pattern).chain_err(|| "Invalid --format-regex pattern")?;
+
+        for group in &["time", "nick", "msg"] {
+            if !regex.capture_names().any(|n| n == Some(*group)) {
+                bail!("--format-regex must define a `{}` named capture group", group);
+            }
+        }
+
+        Ok(LogFormat { name: "custom".to_owned(), regex })
+    }
+
+    /// Names accepted by `--format`.
+    pub fn names() -> &'static [&'static str] {
+        &["irssi", "weechat", "znc"]
+    }
+
+    /// `%H:%M` or `%H:%M:%S`, picked from however many colons `time` captured.
+    pub fn time_format(time: &str) -> &'static str {
+        if time.matches(':').count() > 1 {
+            "%H:%M:%S"
+        } else {
+            "%H:%M"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_known_names() {
+        for name in LogFormat::names() {
+            assert!(LogFormat::builtin(name).is_some());
+        }
+    }
+
+    #[test]
+    fn builtin_unknown_name() {
+        assert!(LogFormat::builtin("no-such-format").is_none());
+    }
+
+    #[test]
+    fn custom_requires_all_named_captures() {
+        assert!(LogFormat::custom(r"(?P<time>.+) (?P<nick>.+) (?P<msg>.+)").is_ok());
+        assert!(LogFormat::custom(r"(?P<time>.+) (?P<msg>.+)").is_err());
+    }
+
+    #[test]
+    fn custom_rejects_invalid_regex() {
+        assert!(LogFormat::custom(r"(?P<time>(").is_err());
+    }
+
+    #[test]
+    fn time_format_picks_width_from_colon_count() {
+        assert_eq!(LogFormat::time_format("12:34"), "%H:%M");
+        assert_eq!(LogFormat::time_format("12:34:56"), "%H:%M:%S");
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeSet;
+
+use fst::{IntoStreamer, Set, Streamer};
+use fst::automaton::Levenshtein;
+
+use tantivy::Index;
+use tantivy::schema::Schema;
+
+use errors::*;
+
+/// Every distinct term seen in the `nick` and `msg` fields, kept as an FST
+/// set so "did you mean ...?" suggestions don't need to scan the whole
+/// term dictionary linearly.
+pub struct TermDictionary {
+    set: Set,
+}
+
+impl TermDictionary {
+    pub fn build(index: &Index, schema: &Schema) -> Result<TermDictionary> {
+        let nick_field = schema.get_field("nick").unwrap();
+        let msg_field = schema.get_field("msg").unwrap();
+
+        let searcher = index.searcher();
+        let mut terms = BTreeSet::new();
+        for segment_reader in searcher.segment_readers() {
+            for &field in &[nick_field, msg_field] {
+                let inverted_index = segment_reader.inverted_index(field);
+                let mut term_stream = inverted_index.terms().stream();
+                while let Some((term_bytes, _)) = term_stream.next() {
+                    if let Ok(term) = ::std::str::from_utf8(term_bytes) {
+                        terms.insert(term.to_owned());
+                    }
+                }
+            }
+        }
+
+        let set = Set::from_iter(terms).chain_err(|| "Can't build term dictionary")?;
+        Ok(TermDictionary { set })
+    }
+
+    /// Dictionary terms within `max_distance` edits of `term`.
+    fn fuzzy_matches(&self, term: &str, max_distance: u32) -> Vec<String> {
+        let lev = match Levenshtein::new(term, max_distance) {
+            Ok(lev) => lev,
+            Err(_) => return Vec::new(),
+        };
+
+        self.set
+            .search(lev)
+            .into_stream()
+            .into_strs()
+            .unwrap_or_default()
+    }
+
+    /// The closest dictionary term to `term`, searched at increasing edit
+    /// distances so a near-exact match is preferred over a distant one.
+    pub fn closest(&self, term: &str) -> Option<String> {
+        for distance in 1..=3 {
+            if let Some(closest) = self.fuzzy_matches(term, distance).into_iter().next() {
+                return Some(closest);
+            }
+        }
+        None
+    }
+}
+
+/// Edit distance budget for fuzzy retrieval: 1 for short terms (where a
+/// bigger budget would match almost anything), 2 for longer ones.
+pub fn edit_distance_for(term: &str) -> u32 {
+    if term.chars().count() <= 5 {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_terms_get_a_tight_budget() {
+        assert_eq!(edit_distance_for("rust"), 1);
+        assert_eq!(edit_distance_for("abcde"), 1);
+    }
+
+    #[test]
+    fn longer_terms_get_a_wider_budget() {
+        assert_eq!(edit_distance_for("abcdef"), 2);
+        assert_eq!(edit_distance_for("programming"), 2);
+    }
+}
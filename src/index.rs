@@ -4,6 +4,8 @@ use std::io::BufReader;
 use std::fs::File;
 use std::time::Instant;
 
+use chrono::NaiveDateTime;
+
 use tantivy::Index;
 use tantivy::schema::*;
 
@@ -12,37 +14,101 @@ use walkdir::WalkDir;
 use regex::Regex;
 
 use errors::*;
+use formats::LogFormat;
+use manifest::{self, Manifest};
 
-lazy_static! {
-    static ref RE: Regex = Regex::new(r"(?x)
-    (?P<time>\d{2}:\d{2})\s
-    [+@&]?
-    \s*
-    (?P<nick>[^\s][^>]+)
-    >
-    \s
-    (?P<msg>.+)").unwrap();
+/// Name of the chrono-backed date field used for range queries (`after:`/`before:`).
+pub const DATE_FIELD: &str = "date";
 
+lazy_static! {
     static ref WS: Regex = Regex::new(r"\s+").unwrap();
 }
 
-pub fn build_index(index_path: &str, data_path: &str) -> Result<()> {
+fn build_schema() -> Schema {
     let mut schema_builder = SchemaBuilder::default();
+    // Kept as a human-readable stored string for display in results.
     schema_builder.add_text_field("time", TEXT | STORED);
     schema_builder.add_text_field("nick", TEXT | STORED);
     schema_builder.add_text_field("msg", TEXT | STORED);
-    let schema = schema_builder.build();
-
-    let index_path = Path::new(index_path);
-    let index = Index::create(index_path, schema.clone())?;
-    let mut index_writer = index.writer(500_000_000)?;
+    // Unix timestamp, indexed as a fast field so `after:`/`before:` can be
+    // turned into a cheap range query instead of a text match.
+    schema_builder.add_i64_field(DATE_FIELD, INT_INDEXED | INT_STORED | FAST);
+    schema_builder.build()
+}
 
+/// Parses and indexes a single log file with `format`, skipping its first
+/// `skip_lines` lines. Returns `(total lines seen, lines indexed, lines that
+/// matched no format)` so callers can both resume correctly and report on
+/// how well `format` fit the file.
+fn index_file(
+    index_writer: &mut ::tantivy::IndexWriter,
+    schema: &Schema,
+    format: &LogFormat,
+    path: &Path,
+    date: &str,
+    skip_lines: usize,
+) -> Result<(usize, usize, usize)> {
     let time_field = schema.get_field("time").unwrap();
     let nick_field = schema.get_field("nick").unwrap();
     let msg_field = schema.get_field("msg").unwrap();
+    let date_field = schema.get_field(DATE_FIELD).unwrap();
+
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+
+    let mut total_lines = 0;
+    let mut indexed = 0;
+    let mut unmatched = 0;
+
+    for line in reader.lines().skip(skip_lines) {
+        let line = line?;
+        total_lines += 1;
+
+        let caps = match format.regex.captures(&line) {
+            Some(m) => m,
+            None => {
+                unmatched += 1;
+                continue;
+            }
+        };
+
+        let time = &caps["time"];
+        let datetime = format!("{} {}", date, time);
+        let datetime_format = format!("%Y-%m-%d {}", LogFormat::time_format(time));
+
+        if WS.is_match(&caps["nick"]) { continue; }
+
+        let mut doc = Document::default();
+        doc.add_text(time_field.clone(), &datetime);
+        doc.add_text(nick_field.clone(), &caps["nick"]);
+        doc.add_text(msg_field.clone(), &caps["msg"]);
+
+        // If the filename's date doesn't parse (or a custom --format-regex's
+        // `time` capture doesn't fit %H:%M/%H:%M:%S), the line still gets
+        // indexed and stays full-text searchable — it just won't show up in
+        // after:/before: range queries.
+        if let Ok(parsed) = NaiveDateTime::parse_from_str(&datetime, &datetime_format) {
+            doc.add_i64(date_field.clone(), parsed.timestamp());
+        }
+
+        index_writer.add_document(doc);
+
+        indexed += 1;
+    }
+
+    Ok((skip_lines + total_lines, indexed, unmatched))
+}
+
+pub fn build_index(index_path: &str, data_path: &str, format: &LogFormat) -> Result<()> {
+    let schema = build_schema();
+
+    let index_path = Path::new(index_path);
+    let index = Index::create(index_path, schema.clone())?;
+    let mut index_writer = index.writer(500_000_000)?;
 
     let mut count = 0;
-    println!("Indexing...");
+    let mut unmatched = 0;
+    println!("Indexing with format '{}'...", format.name);
 
     let now = Instant::now();
     for entry in WalkDir::new(data_path) {
@@ -51,36 +117,90 @@ pub fn build_index(index_path: &str, data_path: &str) -> Result<()> {
         let date = entry.path().file_stem().expect("Can't stem filename");
         let date = date.to_string_lossy();
 
-        let file = File::open(entry.path())?;
-        let reader = BufReader::new(file);
+        let (_, indexed, file_unmatched) =
+            index_file(&mut index_writer, &schema, format, entry.path(), &date, 0)?;
+        count += indexed;
+        unmatched += file_unmatched;
+    }
+    println!("Indexing took {} seconds", now.elapsed().as_secs());
+    let now = Instant::now();
+    index_writer.commit().expect("Can't write index");
+    println!("Writing index took {} seconds", now.elapsed().as_secs());
 
-        for line in reader.lines() {
-            let line = line?;
-            let caps = match RE.captures(&line) {
-                Some(m) => m,
-                None => continue
-            };
+    println!("Indexed {} lines ({} matched no known format)", count, unmatched);
 
-            let datetime = format!("{} {}", date, &caps["time"]);
+    Ok(())
+}
 
-            if WS.is_match(&caps["nick"]) { continue; }
+/// Like `build_index`, but reuses an existing index and only processes the
+/// lines of each log file that weren't indexed last time.
+///
+/// A file whose mtime is unchanged and whose recorded line count matches is
+/// skipped entirely. A file that grew is seeked past its recorded line count
+/// and only the new tail is parsed. The manifest is only written back after
+/// `index_writer.commit()` succeeds, so a crash mid-commit can't desync it
+/// from what's actually on disk.
+pub fn build_index_incremental(index_path: &str, data_path: &str, format: &LogFormat) -> Result<()> {
+    let index_path = Path::new(index_path);
+    let index = Index::open(index_path)?;
+    let schema = index.schema();
+    let mut index_writer = index.writer(500_000_000)?;
 
-            let mut doc = Document::default();
-            doc.add_text(time_field.clone(), &datetime);
-            doc.add_text(nick_field.clone(), &caps["nick"]);
-            doc.add_text(msg_field.clone(),  &caps["msg"]);
-            index_writer.add_document(doc);
+    let manifest_path = manifest::manifest_path(index_path);
+    let mut manifest = Manifest::load(&manifest_path)?;
 
-            count += 1;
+    let mut count = 0;
+    let mut unmatched = 0;
+    let mut files_touched = 0;
+    println!("Updating index with format '{}'...", format.name);
+
+    let now = Instant::now();
+    for entry in WalkDir::new(data_path) {
+        let entry = entry.unwrap();
+        if entry.file_type().is_dir() { continue; }
+        let path = entry.path();
+
+        let metadata = entry.metadata().chain_err(|| "Can't stat log file")?;
+        let mtime = manifest::mtime_secs(&metadata)?;
+
+        if manifest.mtime(path) == Some(mtime) {
+            continue;
         }
+
+        let date = path.file_stem().expect("Can't stem filename");
+        let date = date.to_string_lossy();
+
+        let recorded_lines = manifest.lines_indexed(path);
+        let current_size = metadata.len();
+        // The file is smaller than it was last time we recorded it: it was
+        // rotated (truncated and rewritten) rather than merely appended to,
+        // and `recorded_lines` no longer describes a valid resume point.
+        // Reindex it from the top instead of wedging the manifest at a line
+        // count the file can never reach again. `metadata.len()` is free —
+        // we already stat the file for its mtime — so this needs no extra
+        // read of the file itself.
+        let skip_lines = if current_size < manifest.size(path) { 0 } else { recorded_lines };
+
+        let (total_lines, indexed, file_unmatched) =
+            index_file(&mut index_writer, &schema, format, path, &date, skip_lines)?;
+
+        manifest.record(path, total_lines, mtime, current_size);
+        count += indexed;
+        unmatched += file_unmatched;
+        files_touched += 1;
     }
-    println!("Indexing took {} seconds", now.elapsed().as_secs());
+    println!("Scanning took {} seconds", now.elapsed().as_secs());
+
     let now = Instant::now();
     index_writer.commit().expect("Can't write index");
     println!("Writing index took {} seconds", now.elapsed().as_secs());
 
-    println!("Indexed {} lines", count);
+    manifest.save(&manifest_path)?;
+
+    println!(
+        "Indexed {} new lines across {} changed files ({} matched no known format)",
+        count, files_touched, unmatched
+    );
 
     Ok(())
 }
-
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use errors::*;
+
+/// Per-file bookkeeping so `update` knows how much of a log it already indexed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileState {
+    pub lines_indexed: usize,
+    pub mtime: u64,
+    /// Byte size at the time `lines_indexed` was recorded, so a later run can
+    /// tell a truncated/rotated file (now smaller) from one that just grew,
+    /// without re-reading the whole file to recount its lines.
+    pub size: u64,
+}
+
+/// Tracks, for every log file seen so far, how far indexing has progressed.
+///
+/// Stored as a single JSON file inside the index directory so an `update`
+/// run can tell which files are untouched, which grew, and which are brand
+/// new without re-reading anything.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    files: HashMap<String, FileState>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest> {
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        Ok(serde_json::from_str(&contents).chain_err(|| "Can't parse manifest")?)
+    }
+
+    /// Lines on record as indexed for `path`, or `0` if it's never been seen.
+    /// This does not check whether the file has shrunk or been replaced
+    /// since; callers compare its mtime and size themselves.
+    pub fn lines_indexed(&self, path: &Path) -> usize {
+        self.files
+            .get(&key(path))
+            .map(|state| state.lines_indexed)
+            .unwrap_or(0)
+    }
+
+    pub fn mtime(&self, path: &Path) -> Option<u64> {
+        self.files.get(&key(path)).map(|state| state.mtime)
+    }
+
+    /// Byte size on record for `path`, or `0` if it's never been seen.
+    pub fn size(&self, path: &Path) -> u64 {
+        self.files.get(&key(path)).map(|state| state.size).unwrap_or(0)
+    }
+
+    pub fn record(&mut self, path: &Path, lines_indexed: usize, mtime: u64, size: u64) {
+        self.files.insert(
+            key(path),
+            FileState {
+                lines_indexed,
+                mtime,
+                size,
+            },
+        );
+    }
+
+    /// Write the manifest via a temp file + rename so a crash mid-write
+    /// never leaves a half-written manifest on disk.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let serialized = serde_json::to_string_pretty(self).chain_err(|| "Can't serialize manifest")?;
+
+        let tmp_path = tmp_path_for(path);
+        {
+            let mut tmp_file = File::create(&tmp_path)?;
+            tmp_file.write_all(serialized.as_bytes())?;
+            tmp_file.sync_all()?;
+        }
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+}
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn tmp_path_for(path: &Path) -> PathBuf {
+    let mut tmp = path.to_path_buf();
+    let file_name = tmp
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "manifest.json".to_owned());
+    tmp.set_file_name(format!("{}.tmp", file_name));
+    tmp
+}
+
+/// Path of the manifest file that lives inside a given index directory.
+pub fn manifest_path(index_path: &Path) -> PathBuf {
+    index_path.join("manifest.json")
+}
+
+pub fn mtime_secs(metadata: &fs::Metadata) -> Result<u64> {
+    let modified = metadata.modified()?;
+    let since_epoch = modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .chain_err(|| "File has a modification time before the epoch")?;
+    Ok(since_epoch.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn tmp_manifest_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("irc-search-index-test-{}-{}.json", name, ::std::process::id()))
+    }
+
+    #[test]
+    fn missing_manifest_loads_as_empty() {
+        let path = tmp_manifest_path("missing");
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.lines_indexed(Path::new("foo.log")), 0);
+        assert_eq!(manifest.mtime(Path::new("foo.log")), None);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = tmp_manifest_path("roundtrip");
+
+        let mut manifest = Manifest::default();
+        manifest.record(Path::new("2018-01-01.log"), 42, 1234, 9001);
+        manifest.save(&path).unwrap();
+
+        let loaded = Manifest::load(&path).unwrap();
+        assert_eq!(loaded.lines_indexed(Path::new("2018-01-01.log")), 42);
+        assert_eq!(loaded.mtime(Path::new("2018-01-01.log")), Some(1234));
+        assert_eq!(loaded.size(Path::new("2018-01-01.log")), 9001);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn save_leaves_no_tmp_file_behind() {
+        let path = tmp_manifest_path("notmp");
+
+        let manifest = Manifest::default();
+        manifest.save(&path).unwrap();
+
+        assert!(path.exists());
+        assert!(!tmp_path_for(&path).exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
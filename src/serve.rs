@@ -1,21 +1,36 @@
 use std::path::{Path, PathBuf};
 
+use chrono::NaiveDate;
+
+use regex::Regex;
+
 use rocket;
 use rocket::State;
 use rocket::response::{Redirect, NamedFile};
-use rocket_contrib::Template;
+use rocket_contrib::{Json, Template};
 
-use tantivy::Index;
+use tantivy::{DocAddress, Index, Term};
 use tantivy::schema::*;
 use tantivy::collector::{self, CountCollector, TopCollector};
-use tantivy::query::QueryParser;
+use tantivy::query::{BooleanQuery, FuzzyTermQuery, Occur, Query as QueryTrait, QueryParser, RangeQuery};
 
 use errors::*;
+use facets::{DayCount, FacetCollector, NickCount};
+use fuzzy::{self, TermDictionary};
+use index::DATE_FIELD;
+use snippet;
+
+lazy_static! {
+    // `after:2021-03-01` / `before:2021-03-31`, pulled out of the free-text
+    // query before it reaches the `QueryParser`.
+    static ref DATE_FILTER: Regex = Regex::new(r"(?i)\b(after|before):(\d{4}-\d{2}-\d{2})\b").unwrap();
+}
 
 struct IndexServer {
     index: Index,
     query_parser: QueryParser,
     schema: Schema,
+    term_dict: TermDictionary,
 }
 
 fn init_index(index_path: &str) -> Result<IndexServer> {
@@ -29,10 +44,14 @@ fn init_index(index_path: &str) -> Result<IndexServer> {
 
     let query_parser = QueryParser::new(index.schema(), vec![nick_field, msg_field]);
 
+    println!("Building term dictionary for fuzzy search");
+    let term_dict = TermDictionary::build(&index, &schema)?;
+
     Ok(IndexServer {
         index: index,
         query_parser: query_parser,
         schema: schema,
+        term_dict: term_dict,
     })
 }
 
@@ -45,56 +64,251 @@ fn index_site() -> Redirect {
 struct Query {
     q: Option<String>,
     limit: Option<usize>,
+    offset: Option<usize>,
+    page: Option<usize>,
+    after: Option<String>,
+    before: Option<String>,
+    fuzzy: Option<u8>,
     _search: String,
 }
 
+impl Query {
+    /// `offset` wins if given; otherwise `page` (1-indexed) is turned into
+    /// an offset using the effective page size.
+    fn offset(&self, limit: usize) -> usize {
+        self.offset
+            .or_else(|| self.page.map(|page| page.saturating_sub(1) * limit))
+            .unwrap_or(0)
+    }
+}
+
 #[derive(Serialize)]
 struct SearchResult {
     q: String,
+    after: String,
+    before: String,
+    offset: usize,
     num_hits: usize,
     shown_hits: usize,
     hits: Vec<Hit>,
+    used_fuzzy: bool,
+    suggestion: Option<String>,
+    top_nicks: Vec<NickCount>,
+    activity_by_day: Vec<DayCount>,
     limit_10: bool,
     limit_50: bool,
     limit_100: bool,
 }
 
+impl SearchResult {
+    /// What the JSON route returns when no `q` is given: no query ran, nothing to show.
+    fn empty() -> SearchResult {
+        SearchResult {
+            q: String::new(),
+            after: String::new(),
+            before: String::new(),
+            offset: 0,
+            num_hits: 0,
+            shown_hits: 0,
+            hits: Vec::new(),
+            used_fuzzy: false,
+            suggestion: None,
+            top_nicks: Vec::new(),
+            activity_by_day: Vec::new(),
+            limit_10: false,
+            limit_50: false,
+            limit_100: false,
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct Hit {
     time: String,
     nick: String,
     msg: String,
+    snippet: String,
 }
 
-#[get("/search")]
-fn search_site_no_query() -> Template {
-    Template::render("search", None::<()>)
+/// Pulls `after:`/`before:` filters out of a free-text query, returning the
+/// leftover text plus whichever dates were found.
+fn extract_date_range(user_query: &str) -> (String, Option<NaiveDate>, Option<NaiveDate>) {
+    let mut after = None;
+    let mut before = None;
+
+    for caps in DATE_FILTER.captures_iter(user_query) {
+        let date = NaiveDate::parse_from_str(&caps[2], "%Y-%m-%d").ok();
+        match caps[1].to_lowercase().as_str() {
+            "after" => after = date,
+            "before" => before = date,
+            _ => unreachable!(),
+        }
+    }
+
+    let remaining = DATE_FILTER.replace_all(user_query, "").trim().to_owned();
+    (remaining, after, before)
 }
 
-#[get("/search?<query>")]
-fn search_site(idx: State<IndexServer>, query: Query) -> Result<Template> {
-    if query.q.is_none() {
-        return Ok(Template::render("search", None::<()>));
+/// Builds a `[after 00:00, before 23:59:59]` range query over `DATE_FIELD`,
+/// or `None` if neither bound was given.
+fn date_range_query(schema: &Schema, after: Option<NaiveDate>, before: Option<NaiveDate>) -> Option<Box<QueryTrait>> {
+    if after.is_none() && before.is_none() {
+        return None;
     }
 
-    let user_query = query.q.unwrap();
-    let limit = query.limit.unwrap_or(10);
+    let date_field = schema.get_field(DATE_FIELD).unwrap();
+    let start = after.map(|d| d.and_hms(0, 0, 0).timestamp()).unwrap_or(i64::min_value());
+    let end = before.map(|d| d.and_hms(23, 59, 59).timestamp()).unwrap_or(i64::max_value());
 
-    idx.index.load_searchers()?;
-    let searcher = idx.index.searcher();
+    Some(Box::new(RangeQuery::new_i64(date_field, start..end.saturating_add(1))))
+}
+
+/// A query that matches no documents — used when there's neither free text
+/// nor a date filter to search on (e.g. the search form was submitted blank),
+/// where the right answer is "no results", not an error.
+fn empty_query(schema: &Schema) -> Box<QueryTrait> {
+    let date_field = schema.get_field(DATE_FIELD).unwrap();
+    Box::new(RangeQuery::new_i64(date_field, 0..0))
+}
 
-    let query = idx.query_parser.parse_query(&user_query).expect("Can't parse query");
+/// Intersects the parsed free-text query with an optional date-range query.
+fn combine_queries(schema: &Schema, text_query: Option<Box<QueryTrait>>, date_query: Option<Box<QueryTrait>>) -> Box<QueryTrait> {
+    match (text_query, date_query) {
+        (Some(text), Some(date)) => Box::new(BooleanQuery::from(vec![
+            (Occur::Must, text),
+            (Occur::Must, date),
+        ])),
+        (Some(text), None) => text,
+        (None, Some(date)) => date,
+        (None, None) => empty_query(schema),
+    }
+}
+
+/// Re-issues every word of `text_query_str` as a Levenshtein-bounded term
+/// query against `nick` and `msg`, unioning the results. Used when an exact
+/// query comes back empty, or when the user explicitly asks for `fuzzy=1`.
+fn fuzzy_text_query(schema: &Schema, text_query_str: &str) -> Option<Box<QueryTrait>> {
+    let nick_field = schema.get_field("nick").unwrap();
+    let msg_field = schema.get_field("msg").unwrap();
+
+    let mut clauses = Vec::new();
+    for word in text_query_str.split_whitespace() {
+        let word = word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+        if word.is_empty() { continue; }
+
+        let distance = fuzzy::edit_distance_for(&word);
+        for &field in &[nick_field, msg_field] {
+            let term = Term::from_field_text(field, &word);
+            let fuzzy_query = FuzzyTermQuery::new(term, distance, true);
+            clauses.push((Occur::Should, Box::new(fuzzy_query) as Box<QueryTrait>));
+        }
+    }
+
+    if clauses.is_empty() {
+        None
+    } else {
+        Some(Box::new(BooleanQuery::from(clauses)))
+    }
+}
+
+/// Runs `query_obj` against `index` and returns the total hit count, the
+/// `limit` matching document addresses starting at `offset`, and the
+/// per-nick/per-day facet counts gathered over every match.
+///
+/// `TopCollector` has no notion of an offset, so this collects the first
+/// `offset + limit` hits and slices off the leading `offset` of them.
+fn run_search(
+    index: &Index,
+    schema: &Schema,
+    query_obj: &QueryTrait,
+    limit: usize,
+    offset: usize,
+) -> Result<(usize, Vec<DocAddress>, FacetCollector)> {
+    index.load_searchers()?;
+    let searcher = index.searcher();
+
+    let nick_field = schema.get_field("nick").unwrap();
+    let date_field = schema.get_field(DATE_FIELD).unwrap();
 
     let mut count_collector = CountCollector::default();
-    let mut top_collector = TopCollector::with_limit(limit);
+    let mut top_collector = TopCollector::with_limit(offset + limit);
+    let mut facet_collector = FacetCollector::new(nick_field, date_field);
     {
         let mut chained_collector = collector::chain()
             .push(&mut top_collector)
-            .push(&mut count_collector);
-        searcher.search(&*query, &mut chained_collector)?;
+            .push(&mut count_collector)
+            .push(&mut facet_collector);
+        searcher.search(query_obj, &mut chained_collector)?;
     }
 
-    let doc_addresses = top_collector.docs();
+    let docs = top_collector.docs().into_iter().skip(offset).collect();
+
+    Ok((count_collector.count(), docs, facet_collector))
+}
+
+/// Shared core of the HTML and JSON search routes: parses `query`, runs it
+/// (falling back to fuzzy retrieval if needed), and builds the `SearchResult`
+/// both routes serialize in their own way.
+fn perform_search(idx: &IndexServer, query: &Query) -> Result<SearchResult> {
+    let user_query = query.q.clone().unwrap_or_default();
+    let limit = query.limit.unwrap_or(10);
+    let offset = query.offset(limit);
+    let force_fuzzy = query.fuzzy == Some(1);
+
+    let (text_query_str, inline_after, inline_before) = extract_date_range(&user_query);
+
+    let after = query.after
+        .as_ref()
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        .or(inline_after);
+    let before = query.before
+        .as_ref()
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        .or(inline_before);
+
+    let text_query = if text_query_str.is_empty() {
+        None
+    } else {
+        Some(
+            idx.query_parser
+                .parse_query(&text_query_str)
+                .chain_err(|| format!("Can't parse query '{}'", text_query_str))?,
+        )
+    };
+    let query_obj = combine_queries(&idx.schema, text_query, date_range_query(&idx.schema, after, before));
+
+    let (mut num_hits, mut doc_addresses, mut facets) = run_search(&idx.index, &idx.schema, &*query_obj, limit, offset)?;
+    let mut used_fuzzy = false;
+
+    if (num_hits == 0 || force_fuzzy) && !text_query_str.is_empty() {
+        if let Some(fuzzy_query) = fuzzy_text_query(&idx.schema, &text_query_str) {
+            let combined = combine_queries(&idx.schema, Some(fuzzy_query), date_range_query(&idx.schema, after, before));
+            let (fuzzy_hits, fuzzy_docs, fuzzy_facets) = run_search(&idx.index, &idx.schema, &*combined, limit, offset)?;
+            if fuzzy_hits > 0 {
+                num_hits = fuzzy_hits;
+                doc_addresses = fuzzy_docs;
+                facets = fuzzy_facets;
+                used_fuzzy = true;
+            }
+        }
+    }
+
+    idx.index.load_searchers()?;
+    let searcher = idx.index.searcher();
+
+    // Check every word of the query, not just the first: a typo anywhere in
+    // a multi-word query (e.g. "great pythom") should still surface a
+    // did-you-mean, not only one in the leading word.
+    let suggestion = if num_hits == 0 {
+        text_query_str
+            .split_whitespace()
+            .filter_map(|word| idx.term_dict.closest(&word.to_lowercase()))
+            .next()
+    } else {
+        None
+    };
+
+    let query_terms = snippet::query_terms(&text_query_str);
 
     let hits = doc_addresses
         .into_iter()
@@ -103,38 +317,165 @@ fn search_site(idx: State<IndexServer>, query: Query) -> Result<Template> {
             let doc = idx.schema.to_named_doc(&retrieved_doc);
             let map = doc.0;
 
+            let msg = map["msg"][0].text().to_owned();
+            let snippet = snippet::highlight(&msg, &query_terms);
+
             Hit {
                 time: map["time"][0].text().to_owned(),
                 nick: map["nick"][0].text().to_owned(),
-                msg: map["msg"][0].text().to_owned(),
+                msg: msg,
+                snippet: snippet,
             }
         })
         .collect::<Vec<_>>();
 
-
-    let results = SearchResult {
+    Ok(SearchResult {
         q: user_query,
-        num_hits: count_collector.count(),
+        after: query.after.clone().unwrap_or_default(),
+        before: query.before.clone().unwrap_or_default(),
+        offset: offset,
+        num_hits: num_hits,
         shown_hits: hits.len(),
         hits: hits,
+        used_fuzzy: used_fuzzy,
+        suggestion: suggestion,
+        top_nicks: facets.top_nicks(10),
+        activity_by_day: facets.activity_by_day(),
         limit_10: limit == 10,
         limit_50: limit == 50,
         limit_100: limit == 100,
-    };
+    })
+}
 
+#[get("/search")]
+fn search_site_no_query() -> Template {
+    Template::render("search", None::<()>)
+}
+
+#[get("/search?<query>")]
+fn search_site(idx: State<IndexServer>, query: Query) -> Result<Template> {
+    if query.q.is_none() {
+        return Ok(Template::render("search", None::<()>));
+    }
+
+    let results = perform_search(&idx, &query)?;
     Ok(Template::render("search", results))
 }
 
+#[get("/api/search?<query>")]
+fn search_api(idx: State<IndexServer>, query: Query) -> Result<Json<SearchResult>> {
+    if query.q.is_none() {
+        return Ok(Json(SearchResult::empty()));
+    }
+
+    let results = perform_search(&idx, &query)?;
+    Ok(Json(results))
+}
+
 #[get("/<file..>")]
 fn files(file: PathBuf) -> Option<NamedFile> {
     NamedFile::open(Path::new("static/").join(file)).ok()
 }
+
 pub fn serve(index_path: &str) -> Result<()> {
     rocket::ignite()
-        .mount("/", routes![index_site, search_site_no_query, search_site, files])
+        .mount("/", routes![index_site, search_site_no_query, search_site, search_api, files])
         .attach(Template::fairing())
         .manage(init_index(&index_path)?)
         .launch();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_schema() -> Schema {
+        let mut schema_builder = SchemaBuilder::default();
+        schema_builder.add_text_field("nick", TEXT | STORED);
+        schema_builder.add_text_field("msg", TEXT | STORED);
+        schema_builder.add_i64_field(DATE_FIELD, INT_INDEXED | INT_STORED | FAST);
+        schema_builder.build()
+    }
+
+    fn query_with(offset: Option<usize>, page: Option<usize>) -> Query {
+        Query {
+            q: None,
+            limit: None,
+            offset,
+            page,
+            after: None,
+            before: None,
+            fuzzy: None,
+            _search: String::new(),
+        }
+    }
+
+    #[test]
+    fn offset_explicit_wins_over_page() {
+        let query = query_with(Some(7), Some(3));
+        assert_eq!(query.offset(10), 7);
+    }
+
+    #[test]
+    fn offset_derived_from_page() {
+        let query = query_with(None, Some(3));
+        assert_eq!(query.offset(10), 20);
+    }
+
+    #[test]
+    fn offset_defaults_to_zero() {
+        let query = query_with(None, None);
+        assert_eq!(query.offset(10), 0);
+    }
+
+    #[test]
+    fn offset_page_one_is_zero() {
+        let query = query_with(None, Some(1));
+        assert_eq!(query.offset(10), 0);
+    }
+
+    #[test]
+    fn offset_page_zero_does_not_underflow() {
+        let query = query_with(None, Some(0));
+        assert_eq!(query.offset(10), 0);
+    }
+
+    #[test]
+    fn extract_date_range_pulls_out_after_and_before() {
+        let (remaining, after, before) = extract_date_range("rust after:2021-03-01 before:2021-03-31 lang");
+        assert_eq!(remaining, "rust   lang");
+        assert_eq!(after, NaiveDate::from_ymd_opt(2021, 3, 1));
+        assert_eq!(before, NaiveDate::from_ymd_opt(2021, 3, 31));
+    }
+
+    #[test]
+    fn extract_date_range_is_case_insensitive() {
+        let (_, after, _) = extract_date_range("AFTER:2021-01-01 hi");
+        assert_eq!(after, NaiveDate::from_ymd_opt(2021, 1, 1));
+    }
+
+    #[test]
+    fn extract_date_range_no_filters() {
+        let (remaining, after, before) = extract_date_range("just rust");
+        assert_eq!(remaining, "just rust");
+        assert_eq!(after, None);
+        assert_eq!(before, None);
+    }
+
+    #[test]
+    fn date_range_query_none_when_no_bounds() {
+        let schema = test_schema();
+        assert!(date_range_query(&schema, None, None).is_none());
+    }
+
+    #[test]
+    fn date_range_query_some_when_either_bound_given() {
+        let schema = test_schema();
+        let after = NaiveDate::from_ymd_opt(2021, 1, 1);
+        assert!(date_range_query(&schema, after, None).is_some());
+        assert!(date_range_query(&schema, None, after).is_some());
+        assert!(date_range_query(&schema, after, after).is_some());
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashSet;
+
+/// Tokens the repo considers "words" for snippet purposes: runs of
+/// alphanumerics, lowercased, alongside their byte span in the original text.
+/// This doesn't have to match tantivy's tokenizer exactly, only closely
+/// enough that highlighting lines up with what the query matched.
+fn tokenize(text: &str) -> Vec<(String, usize, usize)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() {
+            if start.is_none() {
+                start = Some(i);
+            }
+        } else if let Some(s) = start.take() {
+            tokens.push((text[s..i].to_lowercase(), s, i));
+        }
+    }
+    if let Some(s) = start {
+        tokens.push((text[s..].to_lowercase(), s, text.len()));
+    }
+
+    tokens
+}
+
+/// Lowercased words appearing anywhere in a user's query string, used to
+/// decide which tokens in a hit's `msg` should be highlighted.
+pub fn query_terms(query: &str) -> HashSet<String> {
+    tokenize(query).into_iter().map(|(term, _, _)| term).collect()
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+const WINDOW: usize = 15;
+
+/// Picks the ~15-token window of `msg` that covers the most distinct query
+/// terms (earliest such window wins ties), and renders it with each matching
+/// token wrapped in `<mark>`. Falls back to the leading window when nothing
+/// in `msg` matches.
+pub fn highlight(msg: &str, terms: &HashSet<String>) -> String {
+    let tokens = tokenize(msg);
+    if tokens.is_empty() {
+        return html_escape(msg);
+    }
+
+    let best_start = if terms.is_empty() {
+        0
+    } else {
+        best_window_start(&tokens, terms).unwrap_or(0)
+    };
+
+    let end = (best_start + WINDOW).min(tokens.len());
+    render_window(msg, &tokens, best_start, end, terms)
+}
+
+fn best_window_start(tokens: &[(String, usize, usize)], terms: &HashSet<String>) -> Option<usize> {
+    let mut best: Option<(usize, usize, usize)> = None; // (coverage, earliest_match, start)
+
+    for start in 0..tokens.len() {
+        let end = (start + WINDOW).min(tokens.len());
+
+        let mut covered = HashSet::new();
+        let mut earliest_match = None;
+        for (i, (term, _, _)) in tokens[start..end].iter().enumerate() {
+            if terms.contains(term) {
+                covered.insert(term.as_str());
+                if earliest_match.is_none() {
+                    earliest_match = Some(start + i);
+                }
+            }
+        }
+
+        if let Some(earliest_match) = earliest_match {
+            let coverage = covered.len();
+            let is_better = match best {
+                None => true,
+                Some((best_coverage, best_earliest, _)) => {
+                    coverage > best_coverage || (coverage == best_coverage && earliest_match < best_earliest)
+                }
+            };
+            if is_better {
+                best = Some((coverage, earliest_match, start));
+            }
+        }
+
+        if end == tokens.len() {
+            break;
+        }
+    }
+
+    best.map(|(_, _, start)| start)
+}
+
+fn render_window(
+    msg: &str,
+    tokens: &[(String, usize, usize)],
+    start: usize,
+    end: usize,
+    terms: &HashSet<String>,
+) -> String {
+    let window = &tokens[start..end];
+    // Only cut the message (and mark the cut with an ellipsis) on a side where
+    // the window leaves tokens out; otherwise keep the surrounding punctuation.
+    let text_start = if start == 0 { 0 } else { window.first().map(|&(_, s, _)| s).unwrap_or(0) };
+    let text_end = if end == tokens.len() {
+        msg.len()
+    } else {
+        window.last().map(|&(_, _, e)| e).unwrap_or_else(|| msg.len())
+    };
+
+    let mut body = String::new();
+    let mut cursor = text_start;
+    for &(ref term, token_start, token_end) in window {
+        body.push_str(&html_escape(&msg[cursor..token_start]));
+        if terms.contains(term) {
+            body.push_str("<mark>");
+            body.push_str(&html_escape(&msg[token_start..token_end]));
+            body.push_str("</mark>");
+        } else {
+            body.push_str(&html_escape(&msg[token_start..token_end]));
+        }
+        cursor = token_end;
+    }
+    body.push_str(&html_escape(&msg[cursor..text_end]));
+
+    let mut snippet = String::new();
+    if text_start > 0 {
+        snippet.push_str("… ");
+    }
+    snippet.push_str(&body);
+    if text_end < msg.len() {
+        snippet.push_str(" …");
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(words: &[&str]) -> HashSet<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn query_terms_lowercases_and_splits_on_words() {
+        let found = query_terms("Hello World");
+        assert!(found.contains("hello"));
+        assert!(found.contains("world"));
+        assert_eq!(found.len(), 2);
+    }
+
+    #[test]
+    fn best_window_start_picks_earliest_full_coverage_window() {
+        let tokens = tokenize("a b c rust is great rust programming");
+        let terms = terms(&["rust", "great"]);
+        // Every window here is short enough to cover both terms at once, so
+        // the earliest matching window (starting at token 0) wins the tie.
+        let start = best_window_start(&tokens, &terms).unwrap();
+        assert_eq!(start, 0);
+    }
+
+    #[test]
+    fn best_window_start_none_when_nothing_matches() {
+        let tokens = tokenize("no overlap here");
+        let terms = terms(&["rust"]);
+        assert_eq!(best_window_start(&tokens, &terms), None);
+    }
+
+    #[test]
+    fn highlight_wraps_matching_terms() {
+        let out = highlight("hello world", &terms(&["world"]));
+        assert_eq!(out, "hello <mark>world</mark>");
+    }
+
+    #[test]
+    fn highlight_escapes_html() {
+        let out = highlight("<script>alert(1)</script>", &HashSet::new());
+        assert!(!out.contains("<script>"));
+        assert!(out.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn highlight_empty_terms_falls_back_to_leading_window() {
+        let out = highlight("just some plain text", &HashSet::new());
+        assert_eq!(out, "just some plain text");
+    }
+}